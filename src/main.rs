@@ -7,9 +7,9 @@ use libc::servent;
 #[cfg(windows)]
 use winapi;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::hash;
-use chrono::{DateTime,Local,TimeZone, Timelike, Duration as ChronoDuration,Utc, format::format};
+use chrono::{DateTime,Local,TimeZone, Timelike, Duration as ChronoDuration,Utc, FixedOffset, format::format};
 use clap::{Command, Arg, ValueEnum, builder::PossibleValue, value_parser, arg, ArgAction, ArgMatches, command};
 use std::mem::zeroed;
 use std::net::UdpSocket;
@@ -17,7 +17,7 @@ use std::time::Duration;
 
 const NTP_MESSAGE_LENGTH: usize = 48;
 const NTP_TD_UNIX_SECONDS: i64 = 2_208_988_800;
-const LOCAL_ADDR : &'static str = "0.0.0.0:12300";
+const LOCAL_ADDR : &'static str = "0.0.0.0:0";
 
 #[derive(Default, Debug, Copy, Clone)]
 struct NTPTimeStamp{
@@ -39,8 +39,8 @@ struct NTPResult{
 
 impl NTPResult {
     fn offset(&self) -> i64 {
-        let delta = self.delay();
-        delta.abs()/2
+        let theta = (self.t2 - self.t1) + (self.t3 - self.t4);
+        theta.num_milliseconds() / 2
     }
 
     fn delay(&self) -> i64 {
@@ -81,7 +81,7 @@ impl NTPMessage {
             data: [0; NTP_MESSAGE_LENGTH],
         }
     }
-    fn client() -> Self {
+    fn client(transmit_time: DateTime<Utc>) -> Self {
         const VERSION: u8 = 0b00_011_000;
         const MODE: u8 = 0b00_000_011;
 
@@ -90,6 +90,10 @@ impl NTPMessage {
         msg.data[0] |= VERSION;
         msg.data[0] |= MODE;
 
+        let transmit_timestamp: NTPTimeStamp = transmit_time.into();
+        msg.write_timestamp(40, transmit_timestamp)
+            .expect("writing into a fixed-size in-memory buffer cannot fail");
+
         msg
     }
 
@@ -104,6 +108,14 @@ impl NTPMessage {
         })
     }
 
+    fn write_timestamp(&mut self, i: usize, ts: NTPTimeStamp) -> Result<(), std::io::Error> {
+        let mut writer = &mut self.data[i..i + 8];
+        writer.write_u32::<BigEndian>(ts.seconds)?;
+        writer.write_u32::<BigEndian>(ts.fraction)?;
+
+        Ok(())
+    }
+
     fn rx_time(&self)->Result<NTPTimeStamp, std::io::Error> {
         self.parse_timestamp(32)
     }
@@ -112,6 +124,54 @@ impl NTPMessage {
         self.parse_timestamp(40)
     }
 
+    fn originate_time(&self)->Result<NTPTimeStamp, std::io::Error> {
+        self.parse_timestamp(24)
+    }
+
+    fn validate_header(&self) -> Result<(), std::io::Error> {
+        let header = self.data[0];
+        let leap_indicator = (header >> 6) & 0b11;
+        let mode = header & 0b111;
+        let stratum = self.data[1];
+
+        if mode != 4 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("expected server mode (4) in reply, got mode {}", mode),
+            ));
+        }
+
+        if leap_indicator == 3 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "server reports an unsynchronized clock (leap indicator 3)",
+            ));
+        }
+
+        if stratum == 0 || stratum > 15 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid or kiss-o'-death stratum: {}", stratum),
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Rejects replays/spoofed replies that don't echo back our own transmit time.
+    fn verify_originate(&self, expected: NTPTimeStamp) -> Result<(), std::io::Error> {
+        let originate = self.originate_time()?;
+
+        if originate.seconds != expected.seconds || originate.fraction != expected.fraction {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "originate timestamp does not match our request; possible spoofed response",
+            ));
+        }
+
+        Ok(())
+    }
+
     fn weighted_mean(values : &[f64], weights: &[f64]) -> f64{
         let mut result = 0.0;
         let mut sum_of_weights = 0.0;
@@ -124,34 +184,43 @@ impl NTPMessage {
         result / sum_of_weights
     }
 
-    fn ntp_roundtrip(host: &str, port: u16)-> Result<NTPResult, std::io::Error>{
-        let destination = format!("{}:{}", host, port);
-        let timeout = Duration::from_secs(1);
+    fn single_roundtrip(host: &str, port: u16, timeout: Duration)-> Result<NTPResult, std::io::Error>{
+        use std::net::ToSocketAddrs;
+
+        let destination = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("could not resolve {}", host),
+            ))?;
 
-        let request = NTPMessage::client();
+        let bind_addr: &str = if destination.is_ipv6() { "[::]:0" } else { LOCAL_ADDR };
+
+        let t1 = Utc::now();
+        let request = NTPMessage::client(t1);
         let mut response = NTPMessage::new();
 
         let message = request.data;
 
-        let udp_connection = UdpSocket::bind(LOCAL_ADDR);
+        let udp = UdpSocket::bind(bind_addr)?;
 
-        let udp = match udp_connection {
-            Ok(udp) =>udp,
-            Err(_err) =>unimplemented!(),
-        };
+        udp.connect(destination)?;
 
-        udp.connect(&destination).expect("unable to connect");
+        let _ = udp.send(&message);
 
-        let t1 = Utc::now();
+        let read_timeout = if timeout.is_zero() { Duration::from_millis(1) } else { timeout };
+        udp.set_read_timeout(Some(read_timeout))?;
 
-        let _ = udp.send(&message);
-        let _ = udp.set_read_timeout(Some(timeout));
-        let _ = udp.recv_from(&mut response.data);
+        udp.recv_from(&mut response.data)?;
         let t4 = Utc::now();
 
-        let t2 : DateTime<Utc> = response.rx_time().unwrap().into();
+        response.validate_header()?;
+        response.verify_originate(request.tx_time()?)?;
+
+        let t2 : DateTime<Utc> = response.rx_time()?.into();
 
-        let t3 : DateTime<Utc> = response.tx_time().unwrap().into();
+        let t3 : DateTime<Utc> = response.tx_time()?.into();
 
         Ok(NTPResult{
             t1,
@@ -161,50 +230,95 @@ impl NTPMessage {
         })
     }
 
-    fn check_time() -> Result<f64, std::io::Error> {
-        const NTP_PORT: u16 = 123;
+    fn ntp_roundtrip(host: &str, port: u16, timeout: Duration, samples: usize)-> Result<NTPResult, std::io::Error>{
+        let mut best: Option<NTPResult> = None;
 
-        let servers = [
-            "time.nist.gov",
-            "time.apple.com",
-            "time.euro.apple.com",
-            "time.google.com",
-            "time2.google.com",
-            //"time.windows.com",
-        ];
+        for _ in 0..samples {
+            let sample = match Self::single_roundtrip(host, port, timeout) {
+                Ok(sample) => sample,
+                Err(_err) => continue,
+            };
 
-        let mut times = Vec::with_capacity(servers.len());
+            best = match best {
+                Some(current) if current.delay() <= sample.delay() => Some(current),
+                _ => Some(sample),
+            };
+        }
 
-        for &server in servers.iter() {
-            print!("{}=>", server);
+        best.ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("no valid NTP samples received from {}", host),
+        ))
+    }
 
-            let calc = Self::ntp_roundtrip(&server, NTP_PORT);
+    fn expand_servers(raw: &str, port: u16) -> Vec<String> {
+        if raw.contains(',') {
+            return raw.split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+        }
 
-            match calc {
-                Ok(time) => {
-                    println!("{}ms away from local system time", time.offset());
-                    times.push(time);
-                }
-                Err(_) => {
-                    println!(" ? [response took too long");
+        use std::net::ToSocketAddrs;
+
+        let lookup = format!("{}:{}", raw, port);
+
+        match lookup.to_socket_addrs() {
+            Ok(addrs) => {
+                let mut seen = std::collections::HashSet::new();
+                let hosts: Vec<String> = addrs
+                    .map(|addr| addr.ip().to_string())
+                    .filter(|ip| seen.insert(ip.clone()))
+                    .collect();
+
+                if hosts.is_empty() {
+                    vec![raw.to_string()]
+                } else {
+                    hosts
                 }
-            };
+            }
+            Err(_err) => vec![raw.to_string()],
         }
+    }
+
+    fn check_time(servers: &[String], timeout: Duration, samples: usize) -> Result<f64, std::io::Error> {
+        const NTP_PORT: u16 = 123;
+
+        let handles: Vec<_> = servers.iter().cloned().map(|server| {
+            std::thread::spawn(move || {
+                let result = Self::ntp_roundtrip(&server, NTP_PORT, timeout, samples);
+                (server, result)
+            })
+        }).collect();
 
         let mut offsets = Vec::with_capacity(servers.len());
         let mut offset_weights = Vec::with_capacity(servers.len());
 
-        for time in &times {
-            let offset = time.offset() as f64;
-            let delay = time.delay() as f64;
+        println!("{:<24}{:>12}{:>12}{:>12}", "server", "offset(ms)", "delay(ms)", "weight");
 
-            let weight = 1_000_000.0 / (delay * delay);
+        for handle in handles {
+            let (server, result) = handle.join().expect("ntp worker thread panicked");
 
-            if weight.is_finite() {
-                offsets.push(offset);
-                offset_weights.push(weight);
-            }
+            match result {
+                Ok(time) => {
+                    let offset = time.offset() as f64;
+                    let delay = time.delay() as f64;
+                    let weight = 1_000_000.0 / (delay * delay);
+
+                    if weight.is_finite() {
+                        offsets.push(offset);
+                        offset_weights.push(weight);
+                        println!("{:<24}{:>12}{:>12}{:>12.6}", server, offset, delay, weight);
+                    } else {
+                        println!("{:<24}{:>12}{:>12}{:>12}", server, offset, delay, "rejected (zero delay)");
+                    }
+                }
+                Err(err) => {
+                    println!("{:<24}{:>12}{:>12}{:>12}", server, "?", "?", format!("rejected: {}", err));
+                }
+            };
         }
+
         let avg_offset = Self::weighted_mean(&offsets, &offset_weights);
 
         Ok(avg_offset)
@@ -289,6 +403,51 @@ impl Clock {
         }
     }
 
+    const SLEW_STEP_THRESHOLD_SECS: i64 = 5;
+
+    fn slew(offset: ChronoDuration) {
+        if offset.num_seconds().abs() >= Self::SLEW_STEP_THRESHOLD_SECS {
+            Self::set(Utc::now() + offset);
+            return;
+        }
+
+        Self::slew_adjust(offset);
+    }
+
+    #[cfg(not(windows))]
+    fn slew_adjust(offset: ChronoDuration) {
+        use std::mem::zeroed;
+
+        use libc::{adjtime, timeval, time_t, suseconds_t};
+
+        let micros = offset.num_microseconds().unwrap_or(0);
+
+        let mut delta: timeval = unsafe {
+            zeroed()
+        };
+
+        delta.tv_sec = (micros / 1_000_000) as time_t;
+        delta.tv_usec = (micros % 1_000_000) as suseconds_t;
+
+        unsafe {
+            adjtime(&delta as *const timeval, std::ptr::null_mut());
+        }
+    }
+
+    #[cfg(windows)]
+    fn slew_adjust(offset: ChronoDuration) {
+        use winapi::SetSystemTimeAdjustment;
+
+        const MAX_ADJUSTMENT_100NS: i64 = 500_000;
+        let micros = offset.num_microseconds().unwrap_or(0);
+        let magnitude = (micros.abs() * 10).min(MAX_ADJUSTMENT_100NS);
+        let adjustment = (micros.signum() * magnitude) as i32;
+
+        unsafe {
+            SetSystemTimeAdjustment(adjustment as u32, 0);
+        }
+    }
+
 }
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 enum OptionMode
@@ -296,18 +455,20 @@ enum OptionMode
     Get,
     Set,
     CheckNtp,
+    Sync,
 }
 
 impl ValueEnum for OptionMode{
     fn value_variants<'a>() -> &'a [Self] {
-        &[OptionMode::Get, OptionMode::Set, OptionMode::CheckNtp]
+        &[OptionMode::Get, OptionMode::Set, OptionMode::CheckNtp, OptionMode::Sync]
     }
 
     fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
         Some(match self {
             OptionMode::Get => PossibleValue::new("get").help("Get time"),
             OptionMode::Set => PossibleValue::new("set").help("Set time"),
-            OptionMode::CheckNtp => PossibleValue::new("check-ntp").help("check the time, which compare to ntp server")
+            OptionMode::CheckNtp => PossibleValue::new("check-ntp").help("check the time, which compare to ntp server"),
+            OptionMode::Sync => PossibleValue::new("sync").help("gradually discipline the clock towards ntp time, instead of stepping it"),
         })
     }
 }
@@ -320,12 +481,13 @@ impl std::fmt::Display for OptionMode {
             .fmt(f)
     }
 }
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 enum StandardType
 {
     RFC2822,
     RFC3339,
     TIMESTAMP,
+    Custom(String),
 }
 
 impl ValueEnum for StandardType{
@@ -338,24 +500,34 @@ impl ValueEnum for StandardType{
             StandardType::RFC2822 => PossibleValue::new("rfc2822").help("Set Standard with RFC2822"),
             StandardType::RFC3339 => PossibleValue::new("rfc3339").help("Set Standard with RFC3339"),
             StandardType::TIMESTAMP => PossibleValue::new("timestamp").help("Set Standard with Timestamp(UNIX Time)"),
+            StandardType::Custom(_) => PossibleValue::new("custom").help("Use a custom strftime format given by --format"),
         })
     }
 }
 
 impl std::fmt::Display for StandardType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.to_possible_value()
-            .expect("no vlaues are skipped")
-            .get_name()
-            .fmt(f)
+        match self {
+            StandardType::Custom(pattern) => write!(f, "custom format \"{}\"", pattern),
+            _ => self.to_possible_value()
+                .expect("no vlaues are skipped")
+                .get_name()
+                .fmt(f),
+        }
     }
 }
 
+const DEFAULT_NTP_SERVERS: &'static str = "time.nist.gov,time.apple.com,time.euro.apple.com,time.google.com,time2.google.com";
+
 struct Cli {
     command : ArgMatches,
     action_mode : OptionMode,
     standard_type : StandardType,
     date_time : String,
+    timezone : String,
+    servers : String,
+    timeout_secs : u64,
+    samples : usize,
 }
 
 
@@ -392,9 +564,68 @@ impl Cli {
                 .help("When <action> is 'set', apply <datetime>. \
                       Otherwise, ignore.")
                 .required(false)
-                .default_value("")              
+                .default_value("")
+            )
+            .arg(
+                arg!(<format>)
+                .display_order(3)
+                .help("Use a custom strftime pattern instead of -s/--use-standard, \
+                      e.g. \"%Y-%m-%d %H:%M:%S%.3f\"")
+                .short('f')
+                .long("format")
+                .required(false)
+                .default_value("")
+            )
+            .arg(
+                arg!(<timezone>)
+                .display_order(4)
+                .help("Target timezone as a fixed offset, e.g. \"+09:00\". For 'get' \
+                      this converts the displayed time; for 'set' with a naive/ambiguous \
+                      <datetime> this is the zone it's interpreted in.")
+                .short('z')
+                .long("timezone")
+                .visible_alias("tz")
+                .required(false)
+                .default_value("")
+            )
+            .arg(
+                arg!(<servers>)
+                .display_order(5)
+                .help("Comma-separated NTP servers to query for check-ntp/sync, \
+                      or a single pool hostname (e.g. \"pool.ntp.org\") whose \
+                      A/AAAA records are each queried separately")
+                .long("servers")
+                .required(false)
+                .default_value(DEFAULT_NTP_SERVERS)
+            )
+            .arg(
+                arg!(<timeout>)
+                .display_order(6)
+                .help("Per-sample NTP read timeout, in seconds")
+                .long("timeout")
+                .value_parser(value_parser!(u64))
+                .required(false)
+                .default_value("1")
+            )
+            .arg(
+                arg!(<samples>)
+                .display_order(7)
+                .help("Number of samples to take per server for the clock filter")
+                .long("samples")
+                .value_parser(value_parser!(usize))
+                .required(false)
+                .default_value("8")
             ).get_matches();
-        Cli {command : new_command, action_mode : OptionMode::Get, standard_type: StandardType::TIMESTAMP, date_time:String::from("")}
+        Cli {
+            command : new_command,
+            action_mode : OptionMode::Get,
+            standard_type: StandardType::TIMESTAMP,
+            date_time:String::from(""),
+            timezone:String::from(""),
+            servers: String::from(DEFAULT_NTP_SERVERS),
+            timeout_secs: 1,
+            samples: 8,
+        }
     }
 
     fn parse(&mut self) {
@@ -409,6 +640,7 @@ impl Cli {
                 OptionMode::Get => action_type = OptionMode::Get,
                 OptionMode::Set => action_type = OptionMode::Set,
                 OptionMode::CheckNtp => action_type = OptionMode::CheckNtp,
+                OptionMode::Sync => action_type = OptionMode::Sync,
             }
         match args
             .get_one::<StandardType>("Std")
@@ -417,45 +649,139 @@ impl Cli {
                 StandardType::RFC2822 => standard_type = StandardType::RFC2822,
                 StandardType::RFC3339 => standard_type = StandardType::RFC3339,
                 StandardType::TIMESTAMP => standard_type = StandardType::TIMESTAMP,
+                StandardType::Custom(_) => unreachable!("Custom is only ever produced from --format below"),
             }
+
+        let format = args
+            .get_one::<String>("format")
+            .expect("!");
+
+        if !format.is_empty() {
+            standard_type = StandardType::Custom(format.to_string());
+        }
+
         let datetime = args
         .get_one::<String>("datetime")
         .expect("!");
 
+        let timezone = args
+        .get_one::<String>("timezone")
+        .expect("!");
+
+        let servers = args
+        .get_one::<String>("servers")
+        .expect("!");
+
+        let timeout_secs = args
+        .get_one::<u64>("timeout")
+        .expect("'timeout' has a default value and parsing will fail if it's missing");
+
+        let samples = args
+        .get_one::<usize>("samples")
+        .expect("'samples' has a default value and parsing will fail if it's missing");
+
         self.action_mode = action_type;
         self.standard_type = standard_type;
         self.date_time = datetime.to_string();
+        self.timezone = timezone.to_string();
+        self.servers = servers.to_string();
+        self.timeout_secs = *timeout_secs;
+        self.samples = *samples;
     }
 
 }
 
 
 
-fn match_time_str(local_time:DateTime<Local>, standard_type:&StandardType) {
+// FixedOffset's FromStr requires a 2-digit hour (e.g. "+09:00" or "+0900");
+// pad shorthand like "+9" or "+9:00" so that still parses instead of regressing.
+fn normalize_offset(raw: &str) -> std::borrow::Cow<'_, str> {
+    let (sign, body) = match raw.as_bytes().first() {
+        Some(b'+') | Some(b'-') => (&raw[..1], &raw[1..]),
+        _ => return raw.into(),
+    };
+
+    let mut parts = body.splitn(2, ':');
+    let hours = parts.next().unwrap_or("");
+    let minutes = parts.next().unwrap_or("00");
+
+    if hours.is_empty() || hours.len() > 2 || !hours.bytes().all(|b| b.is_ascii_digit()) {
+        return raw.into();
+    }
+
+    format!("{}{:0>2}:{:0>2}", sign, hours, minutes).into()
+}
+
+fn resolve_timezone(raw: &str) -> FixedOffset {
+    normalize_offset(raw).parse::<FixedOffset>()
+        .unwrap_or_else(|err| panic!("invalid timezone offset \"{}\": {}", raw, err))
+}
+
+fn print_time<Tz: TimeZone>(time: &DateTime<Tz>, standard_type: &StandardType)
+    where Tz::Offset: std::fmt::Display
+{
     match standard_type {
-        StandardType::TIMESTAMP =>println!("{}", local_time.timestamp()),
-        StandardType::RFC2822 => println!("{}", local_time.to_rfc2822()),
-        StandardType::RFC3339 => println!("{}", local_time.to_rfc3339()),
-        _ =>println!("Wrong type string, please check to type"),
+        StandardType::TIMESTAMP =>println!("{}", time.timestamp()),
+        StandardType::RFC2822 => println!("{}", time.to_rfc2822()),
+        StandardType::RFC3339 => println!("{}", time.to_rfc3339()),
+        StandardType::Custom(pattern) => {
+            use std::fmt::Write;
+            let err_msg = format!("invalid custom format \"{}\"", pattern);
+            let mut rendered = String::new();
+            write!(rendered, "{}", time.format(pattern)).expect(&err_msg);
+            println!("{}", rendered);
+        }
     }
 }
 
-fn match_setting_time_str(_local_time:DateTime<Local>, date_time:&String, standard_type:&StandardType){
-       let time_parser =  match standard_type {
-                    StandardType::RFC2822 => DateTime::parse_from_rfc2822,
-                    StandardType::RFC3339 => DateTime::parse_from_rfc3339,
-                    _ => unimplemented!(),
-            };
+fn match_time_str(local_time:DateTime<Local>, standard_type:&StandardType, timezone: &str) {
+    if timezone.is_empty() {
+        print_time(&local_time, standard_type);
+        return;
+    }
+
+    let offset = resolve_timezone(timezone);
+    print_time(&local_time.with_timezone(&offset), standard_type);
+}
 
+fn match_setting_time_str(_local_time:DateTime<Local>, date_time:&String, standard_type:&StandardType, timezone: &str){
         let err_msg = format!("Unable to parse {} according to {}", date_time, standard_type);
 
-        let new_time = time_parser(date_time).expect(&err_msg);
+        let new_time: DateTime<Utc> = match standard_type {
+            StandardType::RFC2822 => DateTime::parse_from_rfc2822(date_time).expect(&err_msg).with_timezone(&Utc),
+            StandardType::RFC3339 => DateTime::parse_from_rfc3339(date_time).expect(&err_msg).with_timezone(&Utc),
+            StandardType::TIMESTAMP => {
+                let secs: i64 = date_time.parse().expect(&err_msg);
+                Utc.timestamp_opt(secs, 0).unwrap()
+            }
+            StandardType::Custom(pattern) => {
+                let naive = chrono::NaiveDateTime::parse_from_str(date_time, pattern).expect(&err_msg);
+
+                if timezone.is_empty() {
+                    Utc.from_utc_datetime(&naive)
+                } else {
+                    let offset = resolve_timezone(timezone);
+                    offset.from_local_datetime(&naive)
+                        .single()
+                        .unwrap_or_else(|| panic!(
+                            "{} is not a valid, unambiguous local time in timezone offset {}",
+                            date_time, timezone,
+                        ))
+                        .with_timezone(&Utc)
+                }
+            }
+        };
 
         Clock::set(new_time);
 }
 
-fn match_check_ntp() {
-    let offset = NTPMessage::check_time().unwrap() as isize;
+fn match_check_ntp(servers: &str, timeout_secs: u64, samples: usize) {
+    const NTP_PORT: u16 = 123;
+
+    let servers = NTPMessage::expand_servers(servers, NTP_PORT);
+    let timeout = Duration::from_secs(timeout_secs);
+
+    let offset = NTPMessage::check_time(&servers, timeout, samples).unwrap() as isize;
 
     let adjust_ms_ = offset.signum() * offset.abs().min(200) / 5;
     let adjust_ms = ChronoDuration::milliseconds(adjust_ms_ as i64);
@@ -465,6 +791,18 @@ fn match_check_ntp() {
     Clock::set(now);
 }
 
+fn match_sync_ntp(servers: &str, timeout_secs: u64, samples: usize) {
+    const NTP_PORT: u16 = 123;
+
+    let servers = NTPMessage::expand_servers(servers, NTP_PORT);
+    let timeout = Duration::from_secs(timeout_secs);
+
+    let offset = NTPMessage::check_time(&servers, timeout, samples).unwrap();
+    let offset_duration = ChronoDuration::milliseconds(offset as i64);
+
+    Clock::slew(offset_duration);
+}
+
 
 
 fn main() {
@@ -473,15 +811,17 @@ fn main() {
     cli.parse();
     match &cli.action_mode {
         OptionMode::Get =>{
-            match_time_str(now, &cli.standard_type);
+            match_time_str(now, &cli.standard_type, &cli.timezone);
         },
         OptionMode::Set =>{
-            match_setting_time_str(now, &cli.date_time, &cli.standard_type);
+            match_setting_time_str(now, &cli.date_time, &cli.standard_type, &cli.timezone);
         },
         OptionMode::CheckNtp => {
-            match_check_ntp();
+            match_check_ntp(&cli.servers, cli.timeout_secs, cli.samples);
+        }
+        OptionMode::Sync => {
+            match_sync_ntp(&cli.servers, cli.timeout_secs, cli.samples);
         }
-        _ => println!("{}", now.to_rfc3339()),
     }
 
     let maybe_error = std::io::Error::last_os_error();
@@ -493,3 +833,115 @@ fn main() {
         Some(_) => eprintln!("Unable to set the time: {:?}", maybe_error),
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_is_zero_for_a_perfectly_synced_clock() {
+        let t1 = Utc.timestamp_opt(1_000, 0).unwrap();
+        let result = NTPResult { t1, t2: t1, t3: t1, t4: t1 };
+
+        assert_eq!(result.offset(), 0);
+        assert_eq!(result.delay(), 0);
+    }
+
+    #[test]
+    fn offset_reflects_local_clock_running_behind() {
+        // Server (t2, t3) is 500ms ahead of the local clock (t1, t4), with no network delay.
+        let t1 = Utc.timestamp_opt(1_000, 0).unwrap();
+        let t2 = t1 + ChronoDuration::milliseconds(500);
+        let t3 = t2;
+        let t4 = t1;
+        let result = NTPResult { t1, t2, t3, t4 };
+
+        assert_eq!(result.offset(), 500);
+        assert_eq!(result.delay(), 0);
+    }
+
+    #[test]
+    fn delay_reflects_round_trip_time_net_of_server_processing() {
+        let t1 = Utc.timestamp_opt(1_000, 0).unwrap();
+        let t2 = t1 + ChronoDuration::milliseconds(100);
+        let t3 = t2 + ChronoDuration::milliseconds(10);
+        let t4 = t1 + ChronoDuration::milliseconds(200);
+        let result = NTPResult { t1, t2, t3, t4 };
+
+        assert_eq!(result.delay(), 190);
+    }
+
+    fn server_reply(mode: u8, leap_indicator: u8, stratum: u8) -> NTPMessage {
+        let mut msg = NTPMessage::new();
+        msg.data[0] = (leap_indicator << 6) | mode;
+        msg.data[1] = stratum;
+        msg
+    }
+
+    #[test]
+    fn validate_header_accepts_a_well_formed_server_reply() {
+        let msg = server_reply(4, 0, 1);
+        assert!(msg.validate_header().is_ok());
+    }
+
+    #[test]
+    fn validate_header_rejects_wrong_mode() {
+        let msg = server_reply(3, 0, 1);
+        assert!(msg.validate_header().is_err());
+    }
+
+    #[test]
+    fn validate_header_rejects_unsynchronized_leap_indicator() {
+        let msg = server_reply(4, 3, 1);
+        assert!(msg.validate_header().is_err());
+    }
+
+    #[test]
+    fn validate_header_rejects_invalid_stratum() {
+        assert!(server_reply(4, 0, 0).validate_header().is_err());
+        assert!(server_reply(4, 0, 16).validate_header().is_err());
+    }
+
+    #[test]
+    fn verify_originate_accepts_an_echoed_transmit_time() {
+        let expected = NTPTimeStamp { seconds: 123, fraction: 456 };
+        let mut msg = NTPMessage::new();
+        msg.write_timestamp(24, expected).unwrap();
+
+        assert!(msg.verify_originate(expected).is_ok());
+    }
+
+    #[test]
+    fn verify_originate_rejects_a_mismatched_originate_timestamp() {
+        let expected = NTPTimeStamp { seconds: 123, fraction: 456 };
+        let mut msg = NTPMessage::new();
+        msg.write_timestamp(24, NTPTimeStamp { seconds: 999, fraction: 456 }).unwrap();
+
+        assert!(msg.verify_originate(expected).is_err());
+    }
+
+    #[test]
+    fn expand_servers_splits_a_comma_separated_list_and_trims_whitespace() {
+        let servers = NTPMessage::expand_servers("time.nist.gov, time.apple.com ,,time.google.com", 123);
+        assert_eq!(servers, vec!["time.nist.gov", "time.apple.com", "time.google.com"]);
+    }
+
+    #[test]
+    fn resolve_timezone_accepts_fixed_offsets() {
+        assert_eq!(resolve_timezone("+09:00"), FixedOffset::east_opt(9 * 3600).unwrap());
+        assert_eq!(resolve_timezone("-05:30"), FixedOffset::east_opt(-(5 * 3600 + 30 * 60)).unwrap());
+        assert_eq!(resolve_timezone("+0900"), FixedOffset::east_opt(9 * 3600).unwrap());
+    }
+
+    #[test]
+    fn resolve_timezone_accepts_hour_only_and_single_digit_shorthand() {
+        assert_eq!(resolve_timezone("+02"), FixedOffset::east_opt(2 * 3600).unwrap());
+        assert_eq!(resolve_timezone("+9:00"), FixedOffset::east_opt(9 * 3600).unwrap());
+    }
+
+    #[test]
+    #[should_panic]
+    fn resolve_timezone_rejects_invalid_offsets() {
+        resolve_timezone("nonsense");
+    }
+}